@@ -0,0 +1,283 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2020  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+//! Fragmentation and reassembly of oversized payloads.
+//!
+//! The wire format only carries complete `MESSAGE_TYPE_DATA` messages, so any payload that does
+//! not fit into a single UDP datagram (after crypto overhead) would otherwise be silently
+//! dropped. This module splits such payloads into `MESSAGE_TYPE_FRAGMENT` messages on send and
+//! reassembles them on receive.
+
+use crate::{
+    error::Error,
+    types::{NodeId, NODE_ID_BYTES},
+    util::{Time, TimeSource},
+    MsgBuffer
+};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    collections::HashMap,
+    io::{self, Cursor, Read},
+    marker::PhantomData
+};
+
+/// Header prepended to the payload of every `MESSAGE_TYPE_FRAGMENT` message.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FragmentHeader {
+    /// Monotonic (per peer) id of the fragment group this fragment belongs to.
+    pub group: u16,
+    /// Zero-based index of this fragment within the group.
+    pub index: u8,
+    /// Total number of fragments in the group.
+    pub count: u8,
+    /// Length of the complete, unfragmented payload.
+    pub total_len: u16,
+    /// Byte offset of this fragment's data within the complete payload.
+    ///
+    /// Carried explicitly rather than inferred from the fragment's own length, so that a sender
+    /// using a different chunk size (or any future/alternate fragmenter) can still be reassembled
+    /// correctly.
+    pub offset: u16
+}
+
+impl FragmentHeader {
+    const SIZE: usize = 8;
+
+    fn write_to<W: io::Write>(&self, mut out: W) -> Result<(), io::Error> {
+        out.write_u16::<NetworkEndian>(self.group)?;
+        out.write_u8(self.index)?;
+        out.write_u8(self.count)?;
+        out.write_u16::<NetworkEndian>(self.total_len)?;
+        out.write_u16::<NetworkEndian>(self.offset)?;
+        Ok(())
+    }
+
+    fn read_from<R: Read>(mut r: R) -> Result<Self, io::Error> {
+        let group = r.read_u16::<NetworkEndian>()?;
+        let index = r.read_u8()?;
+        let count = r.read_u8()?;
+        let total_len = r.read_u16::<NetworkEndian>()?;
+        let offset = r.read_u16::<NetworkEndian>()?;
+        Ok(Self { group, index, count, total_len, offset })
+    }
+}
+
+/// Splits oversized payloads into a series of `MESSAGE_TYPE_FRAGMENT` messages and assigns each
+/// peer its own monotonic fragment-group ids.
+pub struct Fragmenter {
+    next_group: HashMap<NodeId, u16>
+}
+
+impl Fragmenter {
+    pub fn new() -> Self {
+        Self { next_group: HashMap::new() }
+    }
+
+    fn next_group_id(&mut self, peer: NodeId) -> u16 {
+        let group = self.next_group.entry(peer).or_insert(0);
+        let id = *group;
+        *group = group.wrapping_add(1);
+        id
+    }
+
+    /// Splits `payload` into fragments of at most `mtu` bytes of cleartext each and writes the
+    /// resulting `MESSAGE_TYPE_FRAGMENT` payloads (header + chunk) into `buffers`.
+    ///
+    /// # Errors
+    /// Returns an error if the payload would need more than 255 fragments to transmit, or does
+    /// not fit in the 16-bit total-length field carried by the fragment header.
+    pub fn split(&mut self, peer: NodeId, payload: &[u8], mtu: usize, buffers: &mut Vec<MsgBuffer>) -> Result<(), Error> {
+        debug_assert!(mtu > FragmentHeader::SIZE);
+        if payload.len() > u16::MAX as usize {
+            return Err(Error::Message("Payload too large to fragment"))
+        }
+        let chunk_size = mtu - FragmentHeader::SIZE;
+        let count = (payload.len() + chunk_size - 1) / chunk_size.max(1);
+        if count > 255 || count == 0 {
+            return Err(Error::Message("Payload too large to fragment"))
+        }
+        let group = self.next_group_id(peer);
+        for (index, chunk) in payload.chunks(chunk_size).enumerate() {
+            let header = FragmentHeader {
+                group,
+                index: index as u8,
+                count: count as u8,
+                total_len: payload.len() as u16,
+                offset: (index * chunk_size) as u16
+            };
+            let mut buffer = MsgBuffer::new();
+            let mut data = Vec::with_capacity(FragmentHeader::SIZE + chunk.len());
+            header.write_to(&mut data).map_err(|_| Error::Message("Failed to encode fragment header"))?;
+            data.extend_from_slice(chunk);
+            buffer.buffer()[..data.len()].copy_from_slice(&data);
+            buffer.set_length(data.len());
+            buffers.push(buffer);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Fragmenter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+struct ReassemblyGroup {
+    data: Vec<u8>,
+    received: Vec<bool>,
+    remaining: usize,
+    total_len: u16,
+    first_seen: Time
+}
+
+/// Buffers incoming fragments until a full payload has been reassembled, bounding memory usage
+/// by rejecting oversized groups and evicting incomplete ones after a timeout.
+pub struct ReassemblyTable<TS: TimeSource> {
+    groups: HashMap<(NodeId, u16), ReassemblyGroup>,
+    timeout: u16,
+    buffer_limit: usize,
+    _time_source: PhantomData<TS>
+}
+
+impl<TS: TimeSource> ReassemblyTable<TS> {
+    /// Creates a new reassembly table.
+    ///
+    /// Incomplete fragment groups are evicted after `timeout` seconds and groups whose declared
+    /// total length exceeds `buffer_limit` bytes are rejected outright.
+    pub fn new(timeout: u16, buffer_limit: usize) -> Self {
+        Self { groups: HashMap::new(), timeout, buffer_limit, _time_source: PhantomData }
+    }
+
+    /// Processes one received `MESSAGE_TYPE_FRAGMENT` message payload (header followed by the
+    /// fragment's data).
+    ///
+    /// Returns the reassembled payload once all fragments of its group have arrived.
+    ///
+    /// # Errors
+    /// Returns an error if the message is truncated or the group's declared size or fragment
+    /// count would exceed `buffer_limit`.
+    pub fn handle_fragment(&mut self, peer: NodeId, data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let mut cursor = Cursor::new(data);
+        let header = FragmentHeader::read_from(&mut cursor).map_err(|_| Error::Message("Truncated fragment"))?;
+        let pos = cursor.position() as usize;
+        let chunk = &data[pos..];
+        if header.total_len as usize > self.buffer_limit || header.count as usize > self.buffer_limit || header.count == 0
+        {
+            return Err(Error::Message("Fragment group too large"))
+        }
+        let key = (peer, header.group);
+        let now = TS::now();
+        let group = self.groups.entry(key).or_insert_with(|| ReassemblyGroup {
+            data: vec![0; header.total_len as usize],
+            received: vec![false; header.count as usize],
+            remaining: header.count as usize,
+            total_len: header.total_len,
+            first_seen: now
+        });
+        if header.index as usize >= group.received.len() || header.total_len != group.total_len {
+            return Err(Error::Message("Inconsistent fragment header"))
+        }
+        if !group.received[header.index as usize] {
+            let offset = header.offset as usize;
+            let end = offset + chunk.len();
+            if end > group.data.len() {
+                return Err(Error::Message("Fragment out of bounds"))
+            }
+            group.data[offset..end].copy_from_slice(chunk);
+            group.received[header.index as usize] = true;
+            group.remaining -= 1;
+        }
+        if group.remaining == 0 {
+            let group = self.groups.remove(&key).expect("Group vanished");
+            Ok(Some(group.data))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Evicts fragment groups that have not completed within the configured timeout.
+    pub fn cleanup(&mut self, now: Time) {
+        let timeout = self.timeout;
+        self.groups.retain(|_, group| now - group.first_seen < timeout as Time);
+    }
+}
+
+
+/// Returns whether `payload` needs to be split into `MESSAGE_TYPE_FRAGMENT` messages to fit a
+/// single `MESSAGE_TYPE_DATA` message of at most `mtu` bytes of cleartext.
+pub fn needs_fragmentation(payload_len: usize, mtu: usize) -> bool {
+    payload_len > mtu
+}
+
+/// Bundles the sending and receiving halves of the fragmentation subsystem into the single
+/// object the data path holds per node: outgoing payloads that exceed the MTU are split via
+/// [`Fragmenter::split`], and incoming `MESSAGE_TYPE_FRAGMENT` messages are fed to
+/// [`ReassemblyTable::handle_fragment`] until a full payload comes back out.
+pub struct Fragmentation<TS: TimeSource> {
+    fragmenter: Fragmenter,
+    reassembly: ReassemblyTable<TS>
+}
+
+impl<TS: TimeSource> Fragmentation<TS> {
+    pub fn new(timeout: u16, buffer_limit: usize) -> Self {
+        Self { fragmenter: Fragmenter::new(), reassembly: ReassemblyTable::new(timeout, buffer_limit) }
+    }
+
+    /// Prepares `payload` for sending to `peer`: splits it into `MESSAGE_TYPE_FRAGMENT` messages
+    /// if it exceeds `mtu`, or returns `None` if it should be sent as a single
+    /// `MESSAGE_TYPE_DATA` message instead.
+    pub fn send(&mut self, peer: NodeId, payload: &[u8], mtu: usize) -> Result<Option<Vec<MsgBuffer>>, Error> {
+        if !needs_fragmentation(payload.len(), mtu) {
+            return Ok(None)
+        }
+        let mut buffers = Vec::new();
+        self.fragmenter.split(peer, payload, mtu, &mut buffers)?;
+        Ok(Some(buffers))
+    }
+
+    /// Processes one received `MESSAGE_TYPE_FRAGMENT` message from `peer`, evicting any
+    /// timed-out groups first, and returns the reassembled payload once complete.
+    pub fn receive(&mut self, peer: NodeId, data: &[u8], now: Time) -> Result<Option<Vec<u8>>, Error> {
+        self.reassembly.cleanup(now);
+        self.reassembly.handle_fragment(peer, data)
+    }
+}
+
+
+#[test]
+fn fragment_header_roundtrip() {
+    let header = FragmentHeader { group: 0x1234, index: 3, count: 5, total_len: 1400, offset: 600 };
+    let mut data = vec![];
+    header.write_to(&mut data).unwrap();
+    let decoded = FragmentHeader::read_from(&data[..]).unwrap();
+    assert_eq!(header, decoded);
+}
+
+
+#[test]
+fn split_small_payload_is_single_fragment() {
+    let mut fragmenter = Fragmenter::new();
+    let mut buffers = vec![];
+    let payload = vec![1, 2, 3, 4];
+    fragmenter.split([0; NODE_ID_BYTES], &payload, 1400, &mut buffers).unwrap();
+    assert_eq!(buffers.len(), 1);
+}
+
+#[test]
+fn split_rejects_payload_larger_than_total_len_field() {
+    let mut fragmenter = Fragmenter::new();
+    let mut buffers = vec![];
+    let payload = vec![0; u16::MAX as usize + 1];
+    assert!(fragmenter.split([0; NODE_ID_BYTES], &payload, 1400, &mut buffers).is_err());
+}
+
+#[test]
+fn split_large_payload_rejects_too_many_fragments() {
+    let mut fragmenter = Fragmenter::new();
+    let mut buffers = vec![];
+    let payload = vec![0; 256 * 10];
+    assert!(fragmenter.split([0; NODE_ID_BYTES], &payload, FragmentHeader::SIZE + 10, &mut buffers).is_err());
+}
@@ -19,6 +19,7 @@ use std::{
 pub const MESSAGE_TYPE_DATA: u8 = 0;
 pub const MESSAGE_TYPE_NODE_INFO: u8 = 1;
 pub const MESSAGE_TYPE_KEEPALIVE: u8 = 2;
+pub const MESSAGE_TYPE_FRAGMENT: u8 = 3;
 pub const MESSAGE_TYPE_CLOSE: u8 = 0xff;
 
 
@@ -31,16 +32,118 @@ pub struct PeerInfo {
     pub addrs: AddrList
 }
 
+/// DHCP-style network configuration that a designated node can advertise to joining peers so
+/// they can apply it to their local tun/tap device.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct NetworkConfig {
+    pub dns_servers: SmallVec<[Ipv4Addr; 4]>,
+    pub router: Option<Ipv4Addr>,
+    pub mtu: Option<u16>,
+    pub domain_search: Vec<String>
+}
+
+impl NetworkConfig {
+    // Option codes follow the DHCP option numbers they mirror (RFC 2132, RFC 3397).
+    const OPTION_DNS_SERVERS: u8 = 6;
+    const OPTION_DOMAIN_SEARCH: u8 = 119;
+    const OPTION_MTU: u8 = 26;
+    const OPTION_ROUTER: u8 = 3;
+
+    fn decode<R: Read>(mut r: &mut Take<R>) -> Result<Self, Error> {
+        let mut config = Self::default();
+        while r.limit() > 0 {
+            let option = r.read_u8().map_err(|_| Error::Message("Truncated message"))?;
+            let len = r.read_u8().map_err(|_| Error::Message("Truncated message"))? as usize;
+            let mut rp = r.take(len as u64);
+            match option {
+                Self::OPTION_ROUTER => {
+                    let mut ip = [0; 4];
+                    rp.read_exact(&mut ip).map_err(|_| Error::Message("Truncated message"))?;
+                    config.router = Some(Ipv4Addr::from(ip))
+                }
+                Self::OPTION_DNS_SERVERS => {
+                    while rp.limit() >= 4 {
+                        let mut ip = [0; 4];
+                        rp.read_exact(&mut ip).map_err(|_| Error::Message("Truncated message"))?;
+                        config.dns_servers.push(Ipv4Addr::from(ip));
+                    }
+                }
+                Self::OPTION_MTU => {
+                    config.mtu = Some(rp.read_u16::<NetworkEndian>().map_err(|_| Error::Message("Truncated message"))?)
+                }
+                Self::OPTION_DOMAIN_SEARCH => {
+                    while rp.limit() > 0 {
+                        let name_len = rp.read_u8().map_err(|_| Error::Message("Truncated message"))? as usize;
+                        let mut name = vec![0; name_len];
+                        rp.read_exact(&mut name).map_err(|_| Error::Message("Truncated message"))?;
+                        config
+                            .domain_search
+                            .push(String::from_utf8(name).map_err(|_| Error::Message("Invalid domain name"))?);
+                    }
+                }
+                _ => {
+                    let mut data = vec![0; len];
+                    rp.read_exact(&mut data).map_err(|_| Error::Message("Truncated message"))?;
+                }
+            }
+            r = rp.into_inner();
+        }
+        Ok(config)
+    }
+
+    fn encode<W: Write>(&self, mut out: W) -> Result<(), io::Error> {
+        if let Some(router) = self.router {
+            out.write_u8(Self::OPTION_ROUTER)?;
+            out.write_u8(4)?;
+            out.write_all(&router.octets())?;
+        }
+        if !self.dns_servers.is_empty() {
+            let len = self.dns_servers.len() * 4;
+            if len > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Too many DNS servers"))
+            }
+            out.write_u8(Self::OPTION_DNS_SERVERS)?;
+            out.write_u8(len as u8)?;
+            for dns in &self.dns_servers {
+                out.write_all(&dns.octets())?;
+            }
+        }
+        if let Some(mtu) = self.mtu {
+            out.write_u8(Self::OPTION_MTU)?;
+            out.write_u8(2)?;
+            out.write_u16::<NetworkEndian>(mtu)?;
+        }
+        if !self.domain_search.is_empty() {
+            if self.domain_search.iter().any(|d| d.len() > 255) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Domain search entry too long"))
+            }
+            let len: usize = self.domain_search.iter().map(|d| d.len() + 1).sum();
+            if len > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Domain search list too long"))
+            }
+            out.write_u8(Self::OPTION_DOMAIN_SEARCH)?;
+            out.write_u8(len as u8)?;
+            for name in &self.domain_search {
+                out.write_u8(name.len() as u8)?;
+                out.write_all(name.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct NodeInfo {
     pub peers: PeerList,
     pub claims: RangeList,
-    pub peer_timeout: Option<u16>
+    pub peer_timeout: Option<u16>,
+    pub network_config: Option<NetworkConfig>
 }
 
 impl NodeInfo {
     const PART_CLAIMS: u8 = 2;
     const PART_END: u8 = 0;
+    const PART_NETWORK_CONFIG: u8 = 4;
     const PART_PEERS: u8 = 1;
     const PART_PEER_TIMEOUT: u8 = 3;
 
@@ -89,6 +192,7 @@ impl NodeInfo {
         let mut peers = smallvec![];
         let mut claims = smallvec![];
         let mut peer_timeout = None;
+        let mut network_config = None;
         loop {
             let part = r.read_u8().map_err(|_| Error::Message("Truncated message"))?;
             if part == Self::PART_END {
@@ -105,6 +209,7 @@ impl NodeInfo {
                     peer_timeout =
                         Some(rp.read_u16::<NetworkEndian>().map_err(|_| Error::Message("Truncated message"))?)
                 }
+                Self::PART_NETWORK_CONFIG => network_config = Some(NetworkConfig::decode(&mut rp)?),
                 _ => {
                     let mut data = vec![0; part_len];
                     rp.read_exact(&mut data).map_err(|_| Error::Message("Truncated message"))?;
@@ -112,7 +217,7 @@ impl NodeInfo {
             }
             r = rp.into_inner();
         }
-        Ok(Self { peers, claims, peer_timeout })
+        Ok(Self { peers, claims, peer_timeout, network_config })
     }
 
     pub fn decode<R: Read>(r: R) -> Result<Self, Error> {
@@ -186,6 +291,9 @@ impl NodeInfo {
                     cursor.write_u16::<NetworkEndian>(timeout)
                 })?
             }
+            if let Some(network_config) = &self.network_config {
+                Self::encode_part(&mut cursor, Self::PART_NETWORK_CONFIG, |cursor| network_config.encode(cursor))?
+            }
             cursor.write_u8(Self::PART_END)?;
             len = cursor.position() as usize;
         }
@@ -194,7 +302,7 @@ impl NodeInfo {
     }
 
     pub fn encode(&self, buffer: &mut MsgBuffer) {
-        self.encode_internal(buffer).expect("Buffer too small")
+        self.encode_internal(buffer).expect("Buffer too small or network config invalid")
     }
 }
 
@@ -208,3 +316,59 @@ impl Payload for NodeInfo {
         Self::decode(r)
     }
 }
+
+
+#[test]
+fn encode_decode_node_info_without_network_config() {
+    let msg = NodeInfo {
+        peers: smallvec![],
+        claims: smallvec![],
+        peer_timeout: Some(300),
+        network_config: None
+    };
+    let mut buffer = MsgBuffer::new();
+    msg.encode(&mut buffer);
+    let decoded = NodeInfo::decode(buffer.message()).unwrap();
+    assert_eq!(msg, decoded);
+}
+
+#[test]
+fn encode_decode_node_info_with_network_config() {
+    let network_config = NetworkConfig {
+        dns_servers: smallvec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(8, 8, 4, 4)],
+        router: Some(Ipv4Addr::new(192, 168, 1, 1)),
+        mtu: Some(1400),
+        domain_search: vec!["example.com".to_string(), "internal.example.com".to_string()]
+    };
+    let msg = NodeInfo { peers: smallvec![], claims: smallvec![], peer_timeout: None, network_config: Some(network_config) };
+    let mut buffer = MsgBuffer::new();
+    msg.encode(&mut buffer);
+    let decoded = NodeInfo::decode(buffer.message()).unwrap();
+    assert_eq!(msg, decoded);
+}
+
+#[test]
+fn decode_network_config_ignores_unknown_options() {
+    let mut data = vec![];
+    // unknown option 200 with 3 bytes of payload should be skipped without error
+    data.extend_from_slice(&[200, 3, 1, 2, 3]);
+    data.extend_from_slice(&[NetworkConfig::OPTION_MTU, 2, 0x05, 0xdc]);
+    let mut cursor = Cursor::new(&data[..]);
+    let mut take = Read::take(&mut cursor, data.len() as u64);
+    let config = NetworkConfig::decode(&mut take).unwrap();
+    assert_eq!(config.mtu, Some(1500));
+}
+
+#[test]
+fn encode_network_config_rejects_oversized_dns_list() {
+    let config = NetworkConfig { dns_servers: (0..64).map(|i| Ipv4Addr::new(10, 0, 0, i)).collect(), ..Default::default() };
+    let mut out = vec![];
+    assert!(config.encode(&mut out).is_err());
+}
+
+#[test]
+fn encode_network_config_rejects_oversized_domain_search_list() {
+    let config = NetworkConfig { domain_search: vec!["a".repeat(250), "b".repeat(250)], ..Default::default() };
+    let mut out = vec![];
+    assert!(config.encode(&mut out).is_err());
+}
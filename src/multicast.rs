@@ -0,0 +1,89 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2020  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+//! Multicast/broadcast-aware forwarding via IGMP/MLD snooping.
+//!
+//! Instead of flooding every multicast packet to all peers, the switch/routing layer can snoop
+//! on IGMP and MLD membership reports (see [`crate::payload::Packet::parse_multicast_report`])
+//! to learn which peers are actually listening on a given multicast group, and forward only to
+//! those. Groups with no known listeners still fall back to flooding.
+
+use crate::{
+    error::Error,
+    payload::{MulticastReport, Packet},
+    types::{Address, NodeId},
+    util::{Time, TimeSource}
+};
+use std::{collections::HashMap, marker::PhantomData};
+
+/// Default time (in seconds) a membership report is considered valid for, mirroring the IGMP/MLD
+/// "membership interval" after which a querier expects a refresh.
+pub const DEFAULT_MEMBERSHIP_INTERVAL: u16 = 260;
+
+/// Tracks which peers have reported interest in which multicast groups, expiring stale entries
+/// the same way the switch table ages out learned MAC addresses.
+pub struct MulticastGroupTable<TS: TimeSource> {
+    groups: HashMap<Address, HashMap<NodeId, Time>>,
+    membership_interval: u16,
+    _time_source: PhantomData<TS>
+}
+
+impl<TS: TimeSource> MulticastGroupTable<TS> {
+    /// Creates a new, empty group table that expires memberships after `membership_interval`
+    /// seconds without a refreshing report.
+    pub fn new(membership_interval: u16) -> Self {
+        Self { groups: HashMap::new(), membership_interval, _time_source: PhantomData }
+    }
+
+    /// Records that `peer` has joined (or refreshed its membership of) `group`.
+    pub fn join(&mut self, group: Address, peer: NodeId) {
+        self.groups.entry(group).or_insert_with(HashMap::new).insert(peer, TS::now());
+    }
+
+    /// Records that `peer` has explicitly left `group`.
+    pub fn leave(&mut self, group: Address, peer: NodeId) {
+        if let Some(peers) = self.groups.get_mut(&group) {
+            peers.remove(&peer);
+            if peers.is_empty() {
+                self.groups.remove(&group);
+            }
+        }
+    }
+
+    /// Returns the peers known to be listening on `group`, or `None` if the group has no known
+    /// listeners (the caller should then fall back to flooding).
+    pub fn peers_for_group(&self, group: &Address) -> Option<Vec<NodeId>> {
+        self.groups.get(group).map(|peers| peers.keys().cloned().collect())
+    }
+
+    /// Inspects a packet received from `peer` for an IGMP/MLD membership report and updates the
+    /// group table accordingly. This is the snooping entry point the switch/routing forwarding
+    /// path calls for every packet before deciding whether to flood or fan out to known members.
+    ///
+    /// # Errors
+    /// Propagates any parse error from [`Packet::parse_multicast_report`].
+    pub fn observe_report(&mut self, peer: NodeId, packet_data: &[u8]) -> Result<(), Error> {
+        match Packet::parse_multicast_report(packet_data)? {
+            Some(MulticastReport::Join(group)) => self.join(group, peer),
+            Some(MulticastReport::Leave(group)) => self.leave(group, peer),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Removes memberships that have not been refreshed within the configured interval.
+    pub fn cleanup(&mut self, now: Time) {
+        let interval = self.membership_interval;
+        self.groups.retain(|_, peers| {
+            peers.retain(|_, reported_at| now - *reported_at < interval as Time);
+            !peers.is_empty()
+        });
+    }
+}
+
+impl<TS: TimeSource> Default for MulticastGroupTable<TS> {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMBERSHIP_INTERVAL)
+    }
+}
@@ -0,0 +1,398 @@
+// VpnCloud - Peer-to-Peer VPN
+// Copyright (C) 2015-2020  Dennis Schwerdel
+// This software is licensed under GPL-3 or newer (see LICENSE.md)
+
+//! Local responder for ARP and IPv6 Neighbor Discovery ("neighbor proxy" mode).
+//!
+//! In tap/switch mode, ARP requests and IPv6 Neighbor Solicitations are broadcast/multicast and
+//! would normally be flooded to every peer. When neighbor proxy mode is enabled, such queries are
+//! instead answered locally from the switch table's learned MAC/address bindings, which avoids
+//! flooding the overlay proportionally to the size of the mesh. Callers are expected to look up
+//! the queried address in their own tables and pass the resulting MAC (if any) to
+//! [`build_reply`]; when the lookup comes back empty, the query should still be flooded as usual.
+
+use crate::error::Error;
+use byteorder::{NetworkEndian, WriteBytesExt};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ARP_OPCODE_REQUEST: u16 = 1;
+const ARP_OPCODE_REPLY: u16 = 2;
+const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// A neighbor-resolution query that the neighbor proxy can potentially answer locally.
+#[derive(Debug, PartialEq, Clone)]
+pub enum NeighborQuery {
+    /// An ARP request asking for the MAC address owning `target_ip`.
+    ArpRequest { sender_mac: [u8; 6], sender_ip: Ipv4Addr, target_ip: Ipv4Addr },
+    /// An IPv6 Neighbor Solicitation asking for the MAC address owning `target_ip`.
+    NeighborSolicitation { sender_mac: [u8; 6], sender_ip: Ipv6Addr, target_ip: Ipv6Addr }
+}
+
+impl NeighborQuery {
+    /// The address this query is trying to resolve, to be looked up in the switch table.
+    pub fn target(&self) -> std::net::IpAddr {
+        match self {
+            Self::ArpRequest { target_ip, .. } => std::net::IpAddr::V4(*target_ip),
+            Self::NeighborSolicitation { target_ip, .. } => std::net::IpAddr::V6(*target_ip)
+        }
+    }
+
+    /// Returns this query with `sender_mac` set to `mac`.
+    ///
+    /// Used to replace whatever (possibly absent) sender MAC was parsed out of the payload itself
+    /// with the frame's actual Ethernet source address, which is always present and is what the
+    /// reply must actually be deliverable to.
+    fn with_sender_mac(self, mac: [u8; 6]) -> Self {
+        match self {
+            Self::ArpRequest { sender_ip, target_ip, .. } => Self::ArpRequest { sender_mac: mac, sender_ip, target_ip },
+            Self::NeighborSolicitation { sender_ip, target_ip, .. } => {
+                Self::NeighborSolicitation { sender_mac: mac, sender_ip, target_ip }
+            }
+        }
+    }
+}
+
+/// Configuration for neighbor proxy mode. Disabled by default so pure-bridge behavior (flooding
+/// every ARP request and Neighbor Solicitation to all peers) remains the default.
+#[derive(Debug, Clone, Copy)]
+pub struct NeighborProxyConfig {
+    pub enabled: bool
+}
+
+impl Default for NeighborProxyConfig {
+    fn default() -> Self {
+        Self { enabled: false }
+    }
+}
+
+/// Answers an ethernet frame locally if it is an ARP request or Neighbor Solicitation, neighbor
+/// proxy mode is enabled, and `lookup` resolves the queried address to a known MAC.
+///
+/// This is the single entry point the switch/device layer calls for every frame: on `Some`, the
+/// returned frame should be injected back toward the requesting device instead of forwarding the
+/// original query to any peer; on `None`, the frame should be flooded as usual.
+///
+/// # Errors
+/// Propagates any parse error from [`parse_neighbor_query`].
+pub fn respond_to_frame(
+    frame: &[u8], config: &NeighborProxyConfig, lookup: impl FnOnce(std::net::IpAddr) -> Option<[u8; 6]>
+) -> Result<Option<Vec<u8>>, Error> {
+    if !config.enabled {
+        return Ok(None)
+    }
+    let query = match parse_neighbor_query(frame)? {
+        Some(query) => query,
+        None => return Ok(None)
+    };
+    Ok(lookup(query.target()).map(|target_mac| build_reply(&query, target_mac)))
+}
+
+/// Detects an ARP request or IPv6 Neighbor Solicitation in an ethernet frame.
+///
+/// Returns `Ok(None)` for any frame that is not such a query.
+///
+/// # Errors
+/// This method will fail when the given data is not a valid ethernet frame.
+pub fn parse_neighbor_query(frame: &[u8]) -> Result<Option<NeighborQuery>, Error> {
+    if frame.len() < 14 {
+        return Err(Error::Parse("Frame is too short"))
+    }
+    let mut eth_src = [0; 6];
+    eth_src.copy_from_slice(&frame[6..12]);
+    let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+    // The Ethernet source address is always present and authoritative for where to send the
+    // reply, unlike the ARP sender-hardware-address / NS source-link-layer-address option fields,
+    // which are attacker/application controlled and, for NS, optional in the first place.
+    let query = match ethertype {
+        ETHERTYPE_ARP => parse_arp_request(&frame[14..]),
+        ETHERTYPE_IPV6 => parse_neighbor_solicitation(&frame[14..]),
+        _ => Ok(None)
+    }?;
+    Ok(query.map(|query| query.with_sender_mac(eth_src)))
+}
+
+fn parse_arp_request(data: &[u8]) -> Result<Option<NeighborQuery>, Error> {
+    if data.len() < 28 {
+        return Ok(None)
+    }
+    let opcode = u16::from_be_bytes([data[6], data[7]]);
+    if data[4] != 6 || data[5] != 4 || opcode != ARP_OPCODE_REQUEST {
+        return Ok(None)
+    }
+    let sender_ip = Ipv4Addr::new(data[14], data[15], data[16], data[17]);
+    let target_ip = Ipv4Addr::new(data[24], data[25], data[26], data[27]);
+    Ok(Some(NeighborQuery::ArpRequest { sender_mac: [0; 6], sender_ip, target_ip }))
+}
+
+fn parse_neighbor_solicitation(data: &[u8]) -> Result<Option<NeighborQuery>, Error> {
+    if data.len() < 40 {
+        return Ok(None)
+    }
+    if data[0] >> 4 != 6 {
+        return Ok(None)
+    }
+    let mut next_header = data[6];
+    let mut pos = 40;
+    loop {
+        match next_header {
+            58 => break,
+            0 | 43 | 60 => {
+                if data.len() < pos + 2 {
+                    return Ok(None)
+                }
+                next_header = data[pos];
+                pos += (data[pos + 1] as usize + 1) * 8;
+            }
+            44 => {
+                if data.len() < pos + 8 {
+                    return Ok(None)
+                }
+                next_header = data[pos];
+                pos += 8;
+            }
+            _ => return Ok(None)
+        }
+        if data.len() < pos {
+            return Ok(None)
+        }
+    }
+    let icmp = &data[pos..];
+    if icmp.len() < 24 || icmp[0] != ICMPV6_NEIGHBOR_SOLICITATION {
+        return Ok(None)
+    }
+    let mut sender_ip = [0; 16];
+    sender_ip.copy_from_slice(&data[8..24]);
+    let mut target_ip = [0; 16];
+    target_ip.copy_from_slice(&icmp[8..24]);
+    // The source link-layer address option is optional and, when present, only restates what the
+    // frame's Ethernet source address already carries authoritatively; `parse_neighbor_query`
+    // fills in the real `sender_mac` from that once this query comes back up to it.
+    Ok(Some(NeighborQuery::NeighborSolicitation {
+        sender_mac: [0; 6],
+        sender_ip: Ipv6Addr::from(sender_ip),
+        target_ip: Ipv6Addr::from(target_ip)
+    }))
+}
+
+/// Synthesizes the ARP reply / Neighbor Advertisement frame answering `query`, using
+/// `target_mac` as the locally-known owner of the queried address.
+pub fn build_reply(query: &NeighborQuery, target_mac: [u8; 6]) -> Vec<u8> {
+    match query {
+        NeighborQuery::ArpRequest { sender_mac, sender_ip, target_ip } => {
+            build_arp_reply(*sender_mac, *sender_ip, target_mac, *target_ip)
+        }
+        NeighborQuery::NeighborSolicitation { sender_mac, sender_ip, target_ip } => {
+            build_neighbor_advertisement(*sender_mac, *sender_ip, target_mac, *target_ip)
+        }
+    }
+}
+
+fn build_arp_reply(requester_mac: [u8; 6], requester_ip: Ipv4Addr, target_mac: [u8; 6], target_ip: Ipv4Addr) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(42);
+    frame.extend_from_slice(&requester_mac);
+    frame.extend_from_slice(&target_mac);
+    frame.write_u16::<NetworkEndian>(ETHERTYPE_ARP).unwrap();
+    frame.write_u16::<NetworkEndian>(1).unwrap(); // hardware type: ethernet
+    frame.write_u16::<NetworkEndian>(0x0800).unwrap(); // protocol type: ipv4
+    frame.push(6); // hardware address length
+    frame.push(4); // protocol address length
+    frame.write_u16::<NetworkEndian>(ARP_OPCODE_REPLY).unwrap();
+    frame.extend_from_slice(&target_mac);
+    frame.extend_from_slice(&target_ip.octets());
+    frame.extend_from_slice(&requester_mac);
+    frame.extend_from_slice(&requester_ip.octets());
+    frame
+}
+
+fn build_neighbor_advertisement(
+    requester_mac: [u8; 6], requester_ip: Ipv6Addr, target_mac: [u8; 6], target_ip: Ipv6Addr
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(14 + 40 + 32);
+    frame.extend_from_slice(&requester_mac);
+    frame.extend_from_slice(&target_mac);
+    frame.write_u16::<NetworkEndian>(ETHERTYPE_IPV6).unwrap();
+    // IPv6 header
+    frame.write_u32::<NetworkEndian>(0x6000_0000).unwrap(); // version 6, traffic class/flow label 0
+    frame.write_u16::<NetworkEndian>(32).unwrap(); // payload length: NA (24) + option (8)
+    frame.push(58); // next header: ICMPv6
+    frame.push(255); // hop limit
+    frame.extend_from_slice(&target_ip.octets());
+    frame.extend_from_slice(&requester_ip.octets());
+    // ICMPv6 Neighbor Advertisement, with the checksum field zeroed until computed below.
+    let mut icmp = Vec::with_capacity(32);
+    icmp.push(ICMPV6_NEIGHBOR_ADVERTISEMENT);
+    icmp.push(0); // code
+    icmp.write_u16::<NetworkEndian>(0).unwrap(); // checksum placeholder
+    icmp.write_u32::<NetworkEndian>(0x6000_0000).unwrap(); // flags: solicited + override
+    icmp.extend_from_slice(&target_ip.octets());
+    // Target Link-layer Address option
+    icmp.push(2); // option type
+    icmp.push(1); // length in units of 8 bytes
+    icmp.extend_from_slice(&target_mac);
+    let checksum = icmpv6_checksum(&target_ip, &requester_ip, &icmp);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+    frame.extend_from_slice(&icmp);
+    frame
+}
+
+/// Computes the ICMPv6 checksum (RFC 4443) of `message` as it would appear in a packet sent from
+/// `src` to `dst`, covering the IPv6 pseudo-header followed by `message` itself. `message` must
+/// have its checksum field zeroed.
+fn icmpv6_checksum(src: &Ipv6Addr, dst: &Ipv6Addr, message: &[u8]) -> u16 {
+    let mut pseudo_header = Vec::with_capacity(40 + message.len());
+    pseudo_header.extend_from_slice(&src.octets());
+    pseudo_header.extend_from_slice(&dst.octets());
+    pseudo_header.write_u32::<NetworkEndian>(message.len() as u32).unwrap();
+    pseudo_header.extend_from_slice(&[0, 0, 0]);
+    pseudo_header.push(58); // next header: ICMPv6
+    pseudo_header.extend_from_slice(message);
+    ones_complement_checksum(&pseudo_header)
+}
+
+fn ones_complement_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+
+#[test]
+fn detect_arp_request() {
+    let mut frame = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x06];
+    frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+    frame.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 2]);
+    let query = parse_neighbor_query(&frame).unwrap().unwrap();
+    assert_eq!(
+        query,
+        NeighborQuery::ArpRequest {
+            sender_mac: [1, 2, 3, 4, 5, 6],
+            sender_ip: Ipv4Addr::new(10, 0, 0, 1),
+            target_ip: Ipv4Addr::new(10, 0, 0, 2)
+        }
+    );
+}
+
+#[test]
+fn build_arp_reply_from_request() {
+    let query = NeighborQuery::ArpRequest {
+        sender_mac: [1, 2, 3, 4, 5, 6],
+        sender_ip: Ipv4Addr::new(10, 0, 0, 1),
+        target_ip: Ipv4Addr::new(10, 0, 0, 2)
+    };
+    let target_mac = [6, 5, 4, 3, 2, 1];
+    let reply = build_reply(&query, target_mac);
+    assert_eq!(&reply[0..6], &[1, 2, 3, 4, 5, 6]);
+    assert_eq!(&reply[6..12], &target_mac);
+    assert_eq!(u16::from_be_bytes([reply[20], reply[21]]), ARP_OPCODE_REPLY);
+    assert_eq!(&reply[22..28], &target_mac);
+    assert_eq!(&reply[28..32], &[10, 0, 0, 2]);
+    assert_eq!(&reply[38..42], &[10, 0, 0, 1]);
+}
+
+#[test]
+fn ignore_arp_reply_frame() {
+    let mut frame = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x06];
+    frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 2]);
+    frame.extend_from_slice(&[0; 20]);
+    assert_eq!(parse_neighbor_query(&frame).unwrap(), None);
+}
+
+#[test]
+fn respond_to_frame_is_disabled_by_default() {
+    let mut frame = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x06];
+    frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+    frame.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 2]);
+    let config = NeighborProxyConfig::default();
+    assert_eq!(respond_to_frame(&frame, &config, |_| Some([6, 5, 4, 3, 2, 1])).unwrap(), None);
+}
+
+#[test]
+fn respond_to_frame_falls_back_when_lookup_misses() {
+    let mut frame = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x06];
+    frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+    frame.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 2]);
+    let config = NeighborProxyConfig { enabled: true };
+    assert_eq!(respond_to_frame(&frame, &config, |_| None).unwrap(), None);
+}
+
+#[test]
+fn respond_to_frame_answers_when_enabled_and_known() {
+    let mut frame = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x06];
+    frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+    frame.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 2]);
+    let config = NeighborProxyConfig { enabled: true };
+    let reply = respond_to_frame(&frame, &config, |_| Some([6, 5, 4, 3, 2, 1])).unwrap().unwrap();
+    assert_eq!(&reply[6..12], &[6, 5, 4, 3, 2, 1]);
+}
+
+#[test]
+fn neighbor_solicitation_without_slla_option_uses_ethernet_source_mac() {
+    let mut frame = vec![0x33, 0x33, 0xff, 0, 0, 2, 1, 2, 3, 4, 5, 6, 0x86, 0xdd];
+    frame.extend_from_slice(&[0x60, 0, 0, 0, 0, 24, 58, 255]);
+    frame.extend_from_slice(&Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1).octets()); // source
+    frame.extend_from_slice(&Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 2).octets()); // destination
+    frame.push(135); // ICMPv6 type: Neighbor Solicitation
+    frame.push(0); // code
+    frame.extend_from_slice(&[0, 0]); // checksum (unchecked by the parser)
+    frame.extend_from_slice(&[0, 0, 0, 0]); // reserved
+    frame.extend_from_slice(&Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2).octets()); // target
+    // No source-link-layer-address option follows.
+    let query = parse_neighbor_query(&frame).unwrap().unwrap();
+    assert_eq!(
+        query,
+        NeighborQuery::NeighborSolicitation {
+            sender_mac: [1, 2, 3, 4, 5, 6],
+            sender_ip: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            target_ip: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2)
+        }
+    );
+    let reply = build_reply(&query, [6, 5, 4, 3, 2, 1]);
+    assert_eq!(&reply[0..6], &[1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn neighbor_advertisement_has_valid_checksum() {
+    let query = NeighborQuery::NeighborSolicitation {
+        sender_mac: [1, 2, 3, 4, 5, 6],
+        sender_ip: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+        target_ip: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2)
+    };
+    let target_mac = [6, 5, 4, 3, 2, 1];
+    let reply = build_reply(&query, target_mac);
+    let icmp = &reply[14 + 40..];
+    // A checksum covering its own (non-zero) checksum field folds to the all-ones complement.
+    let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+    let dst = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    let mut pseudo_header = Vec::new();
+    pseudo_header.extend_from_slice(&src.octets());
+    pseudo_header.extend_from_slice(&dst.octets());
+    pseudo_header.write_u32::<NetworkEndian>(icmp.len() as u32).unwrap();
+    pseudo_header.extend_from_slice(&[0, 0, 0]);
+    pseudo_header.push(58);
+    pseudo_header.extend_from_slice(icmp);
+    assert_eq!(ones_complement_checksum(&pseudo_header), 0);
+}
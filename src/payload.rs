@@ -119,6 +119,130 @@ impl Protocol for Packet {
 }
 
 
+/// A membership change reported by an IGMP or MLD control packet.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MulticastReport {
+    /// A peer joined (or continues to listen on) the given multicast group.
+    Join(Address),
+    /// A peer left the given multicast group.
+    Leave(Address)
+}
+
+impl Packet {
+    /// Inspects an ip packet for an IGMPv2/v3 membership report or IGMPv2 leave message (IPv4),
+    /// or an MLD report or MLDv1 done message (ICMPv6 types 131/132/143, reached by walking the
+    /// IPv6 extension header chain) and returns the multicast group membership change it
+    /// carries, if any.
+    ///
+    /// Returns `Ok(None)` for any packet that is none of these IGMP/MLD messages.
+    ///
+    /// # Errors
+    /// This method will fail when the given data is not a valid ipv4 or ipv6 packet.
+    pub fn parse_multicast_report(data: &[u8]) -> Result<Option<MulticastReport>, Error> {
+        if data.is_empty() {
+            return Err(Error::Parse("Empty header"))
+        }
+        match data[0] >> 4 {
+            4 => {
+                if data.len() < 20 {
+                    return Err(Error::Parse("Truncated IPv4 header"))
+                }
+                let ihl = (data[0] & 0x0f) as usize * 4;
+                if data[9] != 2 || data.len() < ihl {
+                    return Ok(None)
+                }
+                Self::parse_igmp(&data[ihl..])
+            }
+            6 => {
+                if data.len() < 40 {
+                    return Err(Error::Parse("Truncated IPv6 header"))
+                }
+                let mut next_header = data[6];
+                let mut pos = 40;
+                // Walk the extension header chain until the upper-layer protocol is found.
+                loop {
+                    match next_header {
+                        58 => {
+                            if data.len() < pos {
+                                return Ok(None)
+                            }
+                            return Self::parse_mld(&data[pos..])
+                        }
+                        0 | 43 | 60 => {
+                            if data.len() < pos + 2 {
+                                return Err(Error::Parse("Truncated IPv6 extension header"))
+                            }
+                            next_header = data[pos];
+                            let ext_len = (data[pos + 1] as usize + 1) * 8;
+                            pos += ext_len;
+                        }
+                        44 => {
+                            if data.len() < pos + 8 {
+                                return Err(Error::Parse("Truncated IPv6 fragment header"))
+                            }
+                            next_header = data[pos];
+                            pos += 8;
+                        }
+                        _ => return Ok(None)
+                    }
+                }
+            }
+            _ => Err(Error::Parse("Invalid IP protocol version"))
+        }
+    }
+
+    fn parse_igmp(data: &[u8]) -> Result<Option<MulticastReport>, Error> {
+        if data.len() < 8 {
+            return Ok(None)
+        }
+        match data[0] {
+            // IGMPv2 Membership Report
+            0x16 => Ok(Some(MulticastReport::Join(Address::read_from_fixed(&data[4..8], 4)?))),
+            // IGMPv2 Leave Group
+            0x17 => Ok(Some(MulticastReport::Leave(Address::read_from_fixed(&data[4..8], 4)?))),
+            // IGMPv3 Membership Report: inspect the first group record's record type and source count.
+            0x22 if data.len() >= 16 => {
+                let group = Address::read_from_fixed(&data[12..16], 4)?;
+                let num_sources = u16::from_be_bytes([data[10], data[11]]);
+                Ok(Some(Self::group_record_report(data[8], num_sources, group)))
+            }
+            _ => Ok(None)
+        }
+    }
+
+    fn parse_mld(data: &[u8]) -> Result<Option<MulticastReport>, Error> {
+        if data.len() < 24 {
+            return Ok(None)
+        }
+        match data[0] {
+            // MLDv1 Listener Report
+            131 => Ok(Some(MulticastReport::Join(Address::read_from_fixed(&data[8..24], 16)?))),
+            // MLDv1 Listener Done
+            132 => Ok(Some(MulticastReport::Leave(Address::read_from_fixed(&data[8..24], 16)?))),
+            // MLDv2 Report: inspect the first group record's record type and source count.
+            143 if data.len() >= 28 => {
+                let group = Address::read_from_fixed(&data[12..28], 16)?;
+                let num_sources = u16::from_be_bytes([data[10], data[11]]);
+                Ok(Some(Self::group_record_report(data[8], num_sources, group)))
+            }
+            _ => Ok(None)
+        }
+    }
+
+    /// Classifies an IGMPv3/MLDv2 group record as a join or leave.
+    ///
+    /// `CHANGE_TO_INCLUDE_MODE` (3) and `BLOCK_OLD_SOURCES` (6) only mean the peer left the group
+    /// entirely when they carry no source addresses; with sources present the peer is still
+    /// requesting traffic from those sources, so it remains a member.
+    fn group_record_report(record_type: u8, num_sources: u16, group: Address) -> MulticastReport {
+        match record_type {
+            3 | 6 if num_sources == 0 => MulticastReport::Leave(group),
+            _ => MulticastReport::Join(group)
+        }
+    }
+}
+
+
 #[test]
 fn decode_ipv4_packet() {
     let data = [0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 1, 1, 192, 168, 1, 2];
@@ -159,3 +283,89 @@ fn decode_invalid_packet() {
     ])
     .is_err());
 }
+
+#[test]
+fn decode_igmpv2_join_and_leave() {
+    let mut data = vec![0x46, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0, 0, 0, 0];
+    data.extend_from_slice(&[0x16, 0, 0, 0, 239, 1, 2, 3]);
+    assert_eq!(
+        Packet::parse_multicast_report(&data).unwrap(),
+        Some(MulticastReport::Join(Address { data: [239, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], len: 4 }))
+    );
+    data[24] = 0x17;
+    assert_eq!(
+        Packet::parse_multicast_report(&data).unwrap(),
+        Some(MulticastReport::Leave(Address { data: [239, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], len: 4 }))
+    );
+}
+
+#[test]
+fn decode_igmpv3_join_and_leave() {
+    let mut data = vec![0x46, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0, 0, 0, 0];
+    // type, reserved, checksum, reserved, num group records, record type (CHANGE_TO_EXCLUDE_MODE), aux data len, num sources, group
+    data.extend_from_slice(&[0x22, 0, 0, 0, 0, 0, 0, 1, 4, 0, 0, 0, 239, 1, 2, 3]);
+    assert_eq!(
+        Packet::parse_multicast_report(&data).unwrap(),
+        Some(MulticastReport::Join(Address { data: [239, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], len: 4 }))
+    );
+    // record type CHANGE_TO_INCLUDE_MODE with no sources: leaves the group entirely
+    data[32] = 3;
+    assert_eq!(
+        Packet::parse_multicast_report(&data).unwrap(),
+        Some(MulticastReport::Leave(Address { data: [239, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], len: 4 }))
+    );
+}
+
+#[test]
+fn decode_igmpv3_source_specific_join_is_not_a_leave() {
+    let mut data = vec![0x46, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0, 0, 0, 0];
+    // record type CHANGE_TO_INCLUDE_MODE with one source: a source-specific join, not a leave
+    data.extend_from_slice(&[0x22, 0, 0, 0, 0, 0, 0, 1, 3, 0, 0, 1, 239, 1, 2, 3]);
+    data.extend_from_slice(&[192, 0, 2, 1]);
+    assert_eq!(
+        Packet::parse_multicast_report(&data).unwrap(),
+        Some(MulticastReport::Join(Address { data: [239, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], len: 4 }))
+    );
+}
+
+#[test]
+fn decode_non_igmp_ipv4_packet_has_no_report() {
+    let data = [0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 1, 1, 192, 168, 1, 2];
+    assert_eq!(Packet::parse_multicast_report(&data).unwrap(), None);
+}
+
+#[test]
+fn decode_mldv1_report_walks_extension_headers() {
+    // IPv6 header with a hop-by-hop extension header (next header 0, length 0 => 8 bytes) before
+    // the ICMPv6 MLD Listener Report.
+    let mut data = vec![0x60, 0, 0, 0, 0, 0, 0, 0];
+    data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6]);
+    data.extend_from_slice(&[0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    data.extend_from_slice(&[58, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend_from_slice(&[131, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend_from_slice(&[0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16]);
+    assert_eq!(
+        Packet::parse_multicast_report(&data).unwrap(),
+        Some(MulticastReport::Join(Address {
+            data: [0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16],
+            len: 16
+        }))
+    );
+}
+
+#[test]
+fn decode_mldv2_source_specific_join_is_not_a_leave() {
+    let mut data = vec![0x60, 0, 0, 0, 0, 0, 58, 0];
+    data.extend_from_slice(&[0; 32]);
+    // type, code, checksum, reserved, num mcast address records, record type (CHANGE_TO_INCLUDE_MODE),
+    // aux data len, num sources (1), multicast address
+    data.extend_from_slice(&[143, 0, 0, 0, 0, 0, 0, 1, 3, 0, 0, 1]);
+    data.extend_from_slice(&[0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16]);
+    assert_eq!(
+        Packet::parse_multicast_report(&data).unwrap(),
+        Some(MulticastReport::Join(Address {
+            data: [0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16],
+            len: 16
+        }))
+    );
+}